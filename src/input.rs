@@ -0,0 +1,265 @@
+//! Reads raw key events off the keyboard's evdev node so the OLED can show
+//! live typing stats, independent of the USB HID report the OLED/lighting
+//! commands go out over.
+
+use anyhow::{Context, Result};
+use evdev::{Device, InputEventKind};
+use std::{
+    collections::VecDeque,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::manager::Message;
+
+/// How far back the rolling words-per-minute figure looks.
+const WPM_WINDOW: Duration = Duration::from_secs(12);
+/// How many recent keycodes to keep for the "last few keys" line.
+const RECENT_CODES_LEN: usize = 6;
+/// How long to sleep between non-blocking reads when the device has nothing
+/// queued, so the read loop still notices `stop` promptly.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads `EV_KEY` events from a keyboard's `/dev/input/eventN` node on its
+/// own thread and forwards each one to the manager as [`Message::KeyEvent`]
+/// (`pressed` is true only for keydowns; releases and autorepeats are false).
+/// Mirrors [`KeyboardWatcher`](crate::manager::KeyboardWatcher)'s lifecycle.
+pub struct InputWatcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl InputWatcher {
+    /// Find the evdev node for a keyboard matching `vendor_id`/`product_id`
+    /// and start reading key events from it.
+    pub fn spawn(vendor_id: u16, product_id: u16, sender: Sender<Message>) -> Result<Self> {
+        let path = find_device_node(vendor_id, product_id)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+
+        let handle = thread::Builder::new()
+            .name(format!("InputWatcher-{:x}:{:x}", vendor_id, product_id))
+            .spawn(move || {
+                if let Err(error) = read_loop(&path, &sender, &thread_stop) {
+                    tracing::error!(?error, "input watcher stopped reading events");
+                }
+            })
+            .context("spawning input watcher thread")?;
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Signal the reader thread to stop and wait for it to exit. Safe to
+    /// call more than once.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            if let Err(error) = handle.join() {
+                tracing::error!(?error, "input watcher thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for InputWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Find the evdev node for a keyboard matching `vendor_id`/`product_id`.
+/// Shared with [`crate::remap`], which needs its own handle on the same
+/// node to grab it for exclusive remapping.
+pub(crate) fn find_device_node(vendor_id: u16, product_id: u16) -> Result<PathBuf> {
+    for entry in std::fs::read_dir("/dev/input").context("listing /dev/input")? {
+        let entry = entry.context("reading /dev/input entry")?;
+        let path = entry.path();
+        let is_event_node = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with("event"));
+        if !is_event_node {
+            continue;
+        }
+
+        let device = match Device::open(&path) {
+            Ok(device) => device,
+            Err(error) => {
+                tracing::warn!(%error, ?path, "could not open input device");
+                continue;
+            }
+        };
+
+        let id = device.input_id();
+        if id.vendor() == vendor_id && id.product() == product_id {
+            return Ok(path);
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Could not find an evdev node for keyboard {:04x}:{:04x}",
+        vendor_id,
+        product_id
+    ))
+}
+
+fn read_loop(path: &Path, sender: &Sender<Message>, stop: &Arc<AtomicBool>) -> Result<()> {
+    let mut device = Device::open(path).context("opening input device")?;
+    set_nonblocking(&device)?;
+    while !stop.load(Ordering::SeqCst) {
+        match device.fetch_events() {
+            Ok(events) => {
+                for event in events {
+                    if stop.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                    if let InputEventKind::Key(key) = event.kind() {
+                        let message = Message::KeyEvent {
+                            code: key.code(),
+                            pressed: event.value() == 1,
+                            at: Instant::now(),
+                        };
+                        if sender.send(message).is_err() {
+                            // Manager is gone; nothing left to read for.
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(error) => return Err(error).context("reading input events"),
+        }
+    }
+    Ok(())
+}
+
+/// Put an evdev node into non-blocking mode so `fetch_events` returns
+/// `WouldBlock` instead of parking the thread.
+pub(crate) fn set_nonblocking(device: &Device) -> Result<()> {
+    let fd = device.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    ensure_fcntl(flags, "reading evdev fd flags")?;
+    let result = unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+    ensure_fcntl(result, "setting evdev fd non-blocking")
+}
+
+fn ensure_fcntl(result: i32, context: &str) -> Result<()> {
+    anyhow::ensure!(
+        result >= 0,
+        "{}: {}",
+        context,
+        std::io::Error::last_os_error()
+    );
+    Ok(())
+}
+
+/// Rolling typing statistics derived from keydown events: words per minute,
+/// a session keystroke counter, and the last few keycodes pressed.
+#[derive(Debug, Default)]
+pub struct TypingStats {
+    keydowns: VecDeque<Instant>,
+    total_keystrokes: u64,
+    recent_codes: VecDeque<u16>,
+}
+
+impl TypingStats {
+    pub fn record_keydown(&mut self, code: u16, at: Instant) {
+        self.keydowns.push_back(at);
+        self.total_keystrokes += 1;
+        self.recent_codes.push_back(code);
+        if self.recent_codes.len() > RECENT_CODES_LEN {
+            self.recent_codes.pop_front();
+        }
+        self.prune(at);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some(&oldest) = self.keydowns.front() {
+            if now.duration_since(oldest) > WPM_WINDOW {
+                self.keydowns.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Rolling words-per-minute over [`WPM_WINDOW`], assuming 5 keystrokes
+    /// per "word" (the usual typing-speed convention).
+    pub fn wpm(&self) -> f32 {
+        if self.keydowns.len() < 2 {
+            return 0.0;
+        }
+        let elapsed = self
+            .keydowns
+            .back()
+            .unwrap()
+            .duration_since(*self.keydowns.front().unwrap())
+            .as_secs_f32()
+            .max(1.0);
+        let words = self.keydowns.len() as f32 / 5.0;
+        words / (elapsed / 60.0)
+    }
+
+    pub fn keystroke_count(&self) -> u64 {
+        self.total_keystrokes
+    }
+
+    pub fn recent_codes(&self) -> impl Iterator<Item = &u16> {
+        self.recent_codes.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_keydown_counts_every_keystroke() {
+        let mut stats = TypingStats::default();
+        let now = Instant::now();
+        stats.record_keydown(30, now);
+        stats.record_keydown(31, now);
+        assert_eq!(stats.keystroke_count(), 2);
+    }
+
+    #[test]
+    fn recent_codes_truncates_at_recent_codes_len() {
+        let mut stats = TypingStats::default();
+        let now = Instant::now();
+        for code in 0..RECENT_CODES_LEN as u16 + 3 {
+            stats.record_keydown(code, now);
+        }
+        let recent: Vec<u16> = stats.recent_codes().copied().collect();
+        assert_eq!(recent.len(), RECENT_CODES_LEN);
+        // The oldest codes (0, 1, 2) should have fallen off the front.
+        assert_eq!(recent[0], 3);
+    }
+
+    #[test]
+    fn prune_drops_keydowns_older_than_wpm_window() {
+        let mut stats = TypingStats::default();
+        let start = Instant::now();
+        stats.record_keydown(30, start);
+        // Second keydown lands well outside WPM_WINDOW of the first.
+        stats.record_keydown(31, start + WPM_WINDOW + Duration::from_secs(1));
+        assert_eq!(stats.keydowns.len(), 1);
+    }
+
+    #[test]
+    fn wpm_is_zero_with_fewer_than_two_samples() {
+        let mut stats = TypingStats::default();
+        stats.record_keydown(30, Instant::now());
+        assert_eq!(stats.wpm(), 0.0);
+    }
+}
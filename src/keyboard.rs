@@ -6,17 +6,25 @@ use bitvec::{order, prelude::BitVec, slice::BitSlice};
 use embedded_graphics::{
     drawable::Pixel, geometry::Point, pixelcolor::BinaryColor, prelude::Size, DrawTarget,
 };
-use rusb::{Device, UsbContext};
+use rusb::{DeviceHandle, UsbContext};
 use tracing::warn;
 
+use crate::lighting::{KeyIndex, Rgb};
+
+/// The only interface we ever write to: the OLED/lighting HID interface.
+const OLED_INTERFACE: u8 = 1;
+
 pub struct KeyboardDevice<K, C>
 where
     K: KeyboardType,
     C: UsbContext,
 {
-    dev: Device<C>,
+    handle: DeviceHandle<C>,
     frame_buffer: BitVec<Msb0, u8>,
     screen_dirty: bool,
+    color_buffer: Vec<Rgb>,
+    lighting_dirty: bool,
+    brightness: u8,
     _keyboard_type: PhantomData<K>,
 }
 
@@ -25,6 +33,8 @@ where
     K: KeyboardType,
     C: UsbContext,
 {
+    /// Open the keyboard's USB handle and claim the OLED interface once,
+    /// kept for the lifetime of this `KeyboardDevice` and released on drop.
     pub fn new(context: &C) -> Result<Self> {
         let dev = context
             .devices()
@@ -50,14 +60,25 @@ where
             .next()
             .ok_or_else(|| anyhow!("Could not find keyboard"))?;
 
+        let mut handle = dev.open().context("Opening USB device for keyboard")?;
+        handle
+            .set_auto_detach_kernel_driver(true)
+            .context("settings auto-detach kernel driver")?;
+        handle
+            .claim_interface(OLED_INTERFACE)
+            .context(format!("claiming interface {}", OLED_INTERFACE))?;
+
         let mut frame_buffer = BitVec::with_capacity(Self::screen_area());
         frame_buffer.resize(Self::screen_area(), false);
 
         Ok(Self {
-            dev,
+            handle,
             _keyboard_type: PhantomData::default(),
             frame_buffer,
             screen_dirty: true,
+            color_buffer: vec![Rgb::BLACK; K::KEY_COUNT],
+            lighting_dirty: true,
+            brightness: 255,
         })
     }
 
@@ -65,27 +86,66 @@ where
         (K::OLED_SIZE.width * K::OLED_SIZE.height) as usize
     }
 
-    fn send(&self, cmd: KeyboardCommand, buf: &[u8]) -> Result<()> {
-        let mut handle = self.dev.open().context("Opening USB device for keyboard")?;
-        const INTERFACE_NUM: u8 = 1;
-        handle
-            .set_auto_detach_kernel_driver(true)
-            .context("settings auto-detach kernel driver")?;
+    /// Set specific keys' colors, leaving the rest of the frame untouched.
+    pub fn set_colors(&mut self, colors: &[(KeyIndex, Rgb)]) -> Result<()> {
+        for (key, color) in colors {
+            let idx = key.0 as usize;
+            ensure!(
+                idx < self.color_buffer.len(),
+                "key index {} out of range for {} keys",
+                idx,
+                self.color_buffer.len()
+            );
+            self.color_buffer[idx] = *color;
+        }
+        self.lighting_dirty = true;
+        self.flush_lighting()
+    }
 
-        let dev_desc = self.dev.device_descriptor()?;
-        for config_num in 0..(dev_desc.num_configurations()) {
-            let config_desc = self.dev.config_descriptor(config_num)?;
-            for iface_num in 0..(config_desc.num_interfaces()) {
-                handle.claim_interface(iface_num).context(format!(
-                    "claiming config {}/{}, interface {}/{}",
-                    config_num,
-                    dev_desc.num_configurations(),
-                    iface_num,
-                    config_desc.num_interfaces(),
-                ))?;
-            }
+    /// Replace the whole per-key color frame. If `frame` matches what's
+    /// already buffered, nothing is sent unless an earlier frame is still
+    /// pending.
+    pub fn set_color_frame(&mut self, frame: &[Rgb]) -> Result<()> {
+        ensure!(
+            frame.len() == self.color_buffer.len(),
+            "lighting frame has {} keys, expected {}",
+            frame.len(),
+            self.color_buffer.len()
+        );
+        if frame != self.color_buffer.as_slice() {
+            self.color_buffer.copy_from_slice(frame);
+            self.lighting_dirty = true;
         }
+        self.flush_lighting()
+    }
 
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.brightness = brightness;
+        self.lighting_dirty = true;
+        self.flush_lighting()
+    }
+
+    pub fn flush_lighting(&mut self) -> Result<()> {
+        if self.lighting_dirty {
+            self.send_colors()?;
+            self.lighting_dirty = false;
+        }
+        Ok(())
+    }
+
+    fn send_colors(&self) -> Result<()> {
+        let mut buf = Vec::with_capacity(1 + self.color_buffer.len() * 3);
+        buf.push(0x05); // lighting report id
+        for color in &self.color_buffer {
+            let color = color.scaled(self.brightness);
+            buf.push(color.r);
+            buf.push(color.g);
+            buf.push(color.b);
+        }
+        self.send(KeyboardCommand::Colors, &buf)
+    }
+
+    fn send(&self, cmd: KeyboardCommand, buf: &[u8]) -> Result<()> {
         let request_type = rusb::request_type(
             rusb::Direction::Out,
             rusb::RequestType::Class,
@@ -93,11 +153,11 @@ where
         );
         assert_eq!(request_type, 0x21);
         let request = 0x09; // what does this mean?
-        let mut remaining_bytes = buf.len();
 
         let timeout = Duration::from_secs(5);
 
-        let bytes_written = handle
+        let bytes_written = self
+            .handle
             .write_control(
                 request_type,
                 request,
@@ -107,7 +167,7 @@ where
                 timeout,
             )
             .context(format!("sending {:?} request", cmd))?;
-        remaining_bytes = remaining_bytes.saturating_sub(bytes_written);
+        let remaining_bytes = buf.len().saturating_sub(bytes_written);
         ensure!(remaining_bytes == 0, "entire request not written");
 
         Ok(())
@@ -130,6 +190,113 @@ where
         let buf: &[u8] = io_buf.as_raw_slice();
         self.send(KeyboardCommand::Oled, buf)
     }
+
+    /// Draw an 8-bit grayscale image using Floyd-Steinberg error diffusion.
+    /// `data` is `width * height` luminance values in raster order;
+    /// out-of-screen pixels are a no-op, like [`draw_pixel`](DrawTarget::draw_pixel).
+    pub fn draw_image_dithered(&mut self, data: &[u8], width: u32, height: u32) -> Result<()> {
+        ensure!(
+            data.len() as u64 == u64::from(width) * u64::from(height),
+            "image data length {} does not match {}x{}",
+            data.len(),
+            width,
+            height
+        );
+        let Size {
+            width: screen_width,
+            height: screen_height,
+        } = K::OLED_SIZE;
+
+        let bits = dither_floyd_steinberg(data, width, height);
+
+        for y in 0..height.min(screen_height) {
+            for x in 0..width.min(screen_width) {
+                let idx = (y * width + x) as usize;
+                let bit_idx = (y * screen_width + x) as usize;
+                if let Some(mut bit) = self.frame_buffer.get_mut(bit_idx) {
+                    *bit = bits[idx];
+                }
+            }
+        }
+
+        self.screen_dirty = true;
+        Ok(())
+    }
+}
+
+/// Floyd-Steinberg dither `width * height` luminance values (raster order)
+/// down to one bit per pixel (`true` = on).
+fn dither_floyd_steinberg(data: &[u8], width: u32, height: u32) -> Vec<bool> {
+    let mut luminance: Vec<f32> = data.iter().map(|&v| v as f32).collect();
+    let mut bits = vec![false; luminance.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            let idx = (y * width + x) as usize;
+            let old = luminance[idx];
+            let new = if old >= 128.0 { 255.0 } else { 0.0 };
+            luminance[idx] = new;
+            bits[idx] = new > 0.0;
+            diffuse_error(&mut luminance, width, height, x, y, old - new);
+        }
+    }
+
+    bits
+}
+
+/// Push a Floyd-Steinberg quantization error out to the not-yet-visited
+/// neighbors of `(x, y)`, skipping any that fall outside the image.
+fn diffuse_error(luminance: &mut [f32], width: u32, height: u32, x: u32, y: u32, err: f32) {
+    const WEIGHTS: [(i64, i64, f32); 4] = [
+        (1, 0, 7.0 / 16.0),
+        (-1, 1, 3.0 / 16.0),
+        (0, 1, 5.0 / 16.0),
+        (1, 1, 1.0 / 16.0),
+    ];
+    for (dx, dy, weight) in WEIGHTS {
+        let nx = x as i64 + dx;
+        let ny = y as i64 + dy;
+        if nx < 0 || ny < 0 || nx as u32 >= width || ny as u32 >= height {
+            continue;
+        }
+        let idx = (ny as u32 * width + nx as u32) as usize;
+        luminance[idx] += err * weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dither_all_black_stays_off() {
+        let bits = dither_floyd_steinberg(&[0; 9], 3, 3);
+        assert!(bits.iter().all(|&on| !on));
+    }
+
+    #[test]
+    fn dither_all_white_stays_on() {
+        let bits = dither_floyd_steinberg(&[255; 9], 3, 3);
+        assert!(bits.iter().all(|&on| on));
+    }
+
+    #[test]
+    fn dither_checkerboard_exact_pattern() {
+        // Already at threshold extremes, so diffusion can't flip neighbors.
+        let data = [0u8, 255, 255, 0];
+        let bits = dither_floyd_steinberg(&data, 2, 2);
+        assert_eq!(bits, vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn dither_mid_gray_diffuses_error_to_neighbors() {
+        // (0,0) quantizes to black; its diffused error pushes (1,0) (127 +
+        // 127*7/16 = 182.5625) over threshold even though it started under it.
+        let data = [127u8, 127, 127, 127];
+        let bits = dither_floyd_steinberg(&data, 2, 2);
+        assert!(!bits[0]);
+        assert!(bits[1]);
+    }
 }
 
 impl<K, C> fmt::Debug for KeyboardDevice<K, C>
@@ -142,9 +309,20 @@ where
     }
 }
 
+impl<K, C> Drop for KeyboardDevice<K, C>
+where
+    K: KeyboardType,
+    C: UsbContext,
+{
+    fn drop(&mut self) {
+        if let Err(error) = self.handle.release_interface(OLED_INTERFACE) {
+            warn!(%error, "failed to release keyboard interface on drop");
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum KeyboardCommand {
-    #[allow(dead_code)]
     Colors,
     #[allow(dead_code)]
     Config {
@@ -175,6 +353,7 @@ pub trait KeyboardType {
     const VENDOR_ID: u16;
     const PRODUCT_ID: u16;
     const OLED_SIZE: Size;
+    const KEY_COUNT: usize;
 
     fn fmt_debug(f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:x?}:{:x?}", Self::VENDOR_ID, Self::PRODUCT_ID)
@@ -191,6 +370,7 @@ impl KeyboardType for ApexProTkl {
         width: 128,
         height: 40,
     };
+    const KEY_COUNT: usize = crate::lighting::KEY_COUNT;
 }
 
 impl<K, Cx> DrawTarget<BinaryColor> for KeyboardDevice<K, Cx>
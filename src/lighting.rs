@@ -0,0 +1,264 @@
+//! Per-key RGB lighting effects for the `KeyboardCommand::Colors` report.
+
+/// Number of individually addressable keys on the ApexProTkl layout.
+pub const KEY_COUNT: usize = 87;
+
+/// Index of a single physical key within the lighting frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeyIndex(pub u8);
+
+/// Physical key order for the ApexProTkl's ANSI TKL layout: the main block
+/// row by row, left-to-right and top-to-bottom (function row, number row,
+/// ... down to the spacebar row), then the navigation, arrow and
+/// print-screen clusters. A keycode's position in this table is its
+/// [`KeyIndex`] in the per-key lighting frame. Values are raw Linux evdev
+/// keycodes (`linux/input-event-codes.h`), kept as plain `u16`s rather than
+/// pulling the `evdev` crate's `Key` enum into this module.
+const EVDEV_CODE_TO_KEY_INDEX: [u16; KEY_COUNT] = [
+    // function row: Esc, F1-F12
+    1, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 87, 88,
+    // number row: `, 1-0, -, =, Backspace
+    41, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14,
+    // qwerty row: Tab, Q-P, [, ], backslash
+    15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 43,
+    // home row: CapsLock, A-L, ;, ', Enter
+    58, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 28,
+    // bottom row: LShift, Z-M, comma, dot, slash, RShift
+    42, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54,
+    // spacebar row: LCtrl, LMeta, LAlt, Space, RAlt, RMeta, Menu, RCtrl
+    29, 125, 56, 57, 100, 126, 127, 97,
+    // navigation cluster
+    110, 102, 104, 111, 107, 109,
+    // arrow cluster
+    103, 105, 108, 106,
+    // print-screen cluster
+    99, 70, 119,
+];
+
+/// Look up the per-key lighting [`KeyIndex`] for a raw evdev keycode, e.g.
+/// as reported by [`Message::KeyEvent`](crate::manager::Message::KeyEvent).
+/// Codes with no entry in the layout (media keys and the like) return
+/// `None`, so they simply don't participate in reactive lighting.
+pub fn key_index_for_code(code: u16) -> Option<KeyIndex> {
+    EVDEV_CODE_TO_KEY_INDEX
+        .iter()
+        .position(|&c| c == code)
+        .map(|pos| KeyIndex(pos as u8))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Scale each channel by `level` out of 255.
+    pub fn scaled(self, level: u8) -> Rgb {
+        let scale = |c: u8| ((c as u16 * level as u16) / 255) as u8;
+        Rgb::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+/// A lighting effect that can be rendered into a per-key color frame.
+#[derive(Debug, Clone)]
+pub enum LightingEffect {
+    /// A fixed color per key.
+    Static(Vec<Rgb>),
+    /// The whole board fades in and out of `color` over `period_ticks`.
+    Breathing { color: Rgb, period_ticks: u32 },
+    /// Keys light up `accent` when pressed; the accent fades linearly toward
+    /// black over `decay_ticks`, after which the key shows `base` again.
+    Reactive {
+        base: Rgb,
+        accent: Rgb,
+        decay_ticks: u32,
+    },
+}
+
+impl Default for LightingEffect {
+    fn default() -> Self {
+        LightingEffect::Static(Vec::new())
+    }
+}
+
+/// Pick a startup lighting effect from `APEX_LIGHTING_EFFECT`
+/// (`static`/`breathing`/`reactive`, default `static`).
+pub fn lighting_effect_from_env() -> LightingEffect {
+    const ACCENT: Rgb = Rgb::new(0, 120, 255);
+    match std::env::var("APEX_LIGHTING_EFFECT").as_deref() {
+        Ok("breathing") => LightingEffect::Breathing {
+            color: ACCENT,
+            period_ticks: 90,
+        },
+        Ok("reactive") => LightingEffect::Reactive {
+            base: Rgb::BLACK,
+            accent: ACCENT,
+            decay_ticks: 15,
+        },
+        _ => LightingEffect::Static(vec![ACCENT; KEY_COUNT]),
+    }
+}
+
+/// Tracks effect state across ticks and renders it into a color frame.
+///
+/// This is the "effects runner": it owns nothing USB-specific, so it can be
+/// driven from the manager's event loop and handed to a [`KeyboardDevice`](crate::keyboard::KeyboardDevice)
+/// only when a frame actually needs to go out over the wire.
+#[derive(Debug, Clone, Default)]
+pub struct EffectRunner {
+    effect: LightingEffect,
+    tick: u32,
+    // Keys currently animating back down from a keypress, with the tick they were pressed at.
+    pressed: Vec<(KeyIndex, u32)>,
+}
+
+impl EffectRunner {
+    pub fn set_effect(&mut self, effect: LightingEffect) {
+        self.effect = effect;
+        self.tick = 0;
+        self.pressed.clear();
+    }
+
+    pub fn register_keypress(&mut self, key: KeyIndex) {
+        self.pressed.retain(|(k, _)| *k != key);
+        self.pressed.push((key, self.tick));
+    }
+
+    pub fn advance(&mut self) {
+        self.tick = self.tick.wrapping_add(1);
+    }
+
+    /// Render the current effect state into a per-key frame, with `brightness`
+    /// (0-255) applied as a final scaling pass.
+    pub fn render(&self, key_count: usize, brightness: u8) -> Vec<Rgb> {
+        let mut frame = vec![Rgb::BLACK; key_count];
+        match &self.effect {
+            LightingEffect::Static(colors) => {
+                for (slot, color) in frame.iter_mut().zip(colors) {
+                    *slot = *color;
+                }
+            }
+            LightingEffect::Breathing {
+                color,
+                period_ticks,
+            } => {
+                let period = (*period_ticks).max(1);
+                let phase = (self.tick % period) as f32 / period as f32;
+                let level = (phase * std::f32::consts::TAU).sin() * 0.5 + 0.5;
+                let color = color.scaled((level * 255.0) as u8);
+                frame.iter_mut().for_each(|slot| *slot = color);
+            }
+            LightingEffect::Reactive {
+                base,
+                accent,
+                decay_ticks,
+            } => {
+                let decay_ticks = (*decay_ticks).max(1);
+                frame.iter_mut().for_each(|slot| *slot = *base);
+                for (key, pressed_at) in &self.pressed {
+                    let idx = key.0 as usize;
+                    if idx >= frame.len() {
+                        continue;
+                    }
+                    let age = self.tick.saturating_sub(*pressed_at);
+                    if age >= decay_ticks {
+                        continue;
+                    }
+                    let level = 255 - ((age * 255) / decay_ticks) as u8;
+                    frame[idx] = accent.scaled(level);
+                }
+            }
+        }
+        frame.iter_mut().for_each(|slot| *slot = slot.scaled(brightness));
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breathing_sine_wave_peaks_and_troughs() {
+        let color = Rgb::new(200, 100, 50);
+        let mut runner = EffectRunner {
+            effect: LightingEffect::Breathing {
+                color,
+                period_ticks: 4,
+            },
+            ..Default::default()
+        };
+        // brightness 255 is a no-op final scale, so these frames reflect
+        // `color.scaled(level)` directly.
+        let trough = vec![color.scaled(127); 1];
+        let peak = vec![color; 1];
+
+        assert_eq!(runner.render(1, 255), trough, "tick 0: phase 0, sin = 0");
+        runner.tick = 1;
+        assert_eq!(runner.render(1, 255), peak, "tick 1: phase 1/4, sin = 1");
+        runner.tick = 3;
+        assert_eq!(
+            runner.render(1, 255),
+            vec![Rgb::BLACK; 1],
+            "tick 3: phase 3/4, sin = -1"
+        );
+        runner.tick = 4;
+        assert_eq!(
+            runner.render(1, 255),
+            trough,
+            "tick 4 wraps back to the same phase as tick 0"
+        );
+    }
+
+    #[test]
+    fn reactive_decays_from_accent_to_base_then_holds() {
+        let base = Rgb::new(10, 10, 10);
+        let accent = Rgb::new(200, 200, 200);
+        let mut runner = EffectRunner {
+            effect: LightingEffect::Reactive {
+                base,
+                accent,
+                decay_ticks: 10,
+            },
+            ..Default::default()
+        };
+        runner.register_keypress(KeyIndex(0));
+
+        assert_eq!(
+            runner.render(1, 255)[0],
+            accent,
+            "just pressed: full accent, no decay yet"
+        );
+
+        runner.tick = 5;
+        assert_eq!(
+            runner.render(1, 255)[0],
+            accent.scaled(128),
+            "halfway through decay_ticks"
+        );
+
+        runner.tick = 10;
+        assert_eq!(
+            runner.render(1, 255)[0],
+            base,
+            "age has reached decay_ticks: fully decayed back to base"
+        );
+    }
+
+    #[test]
+    fn render_applies_brightness_as_a_final_scale() {
+        let runner = EffectRunner {
+            effect: LightingEffect::Static(vec![Rgb::new(255, 255, 255)]),
+            ..Default::default()
+        };
+        assert_eq!(runner.render(1, 128), vec![Rgb::new(255, 255, 255).scaled(128)]);
+    }
+}
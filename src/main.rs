@@ -1,7 +1,10 @@
 #![allow(dead_code, unused_imports)]
 
+mod input;
 mod keyboard;
+mod lighting;
 mod manager;
+mod remap;
 
 use std::{convert::TryInto, env::args, time::Duration};
 
@@ -14,11 +17,11 @@ use embedded_graphics::{
     primitives::{Circle, Rectangle},
     style::{PrimitiveStyle, TextStyle},
 };
-use keyboard::KeyboardDevice;
+use keyboard::{ApexProTkl, KeyboardDevice};
 use rusb::{Context, Hotplug, UsbContext};
 use tracing_subscriber::EnvFilter;
 
-use crate::{keyboard::KeyboardInfo, manager::KeyboardManager};
+use crate::manager::KeyboardManager;
 
 fn main() -> Result<()> {
     ensure!(rusb::has_hotplug(), "No hotplug functionality available");
@@ -31,14 +34,11 @@ fn main() -> Result<()> {
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let keyboard_info = KeyboardInfo {
-        vendor_id: 0x1038,
-        product_id: 0x1614,
-        screen_size: Size::new(128, 40),
-    };
-
     let context = rusb::Context::new()?;
-    let manager = KeyboardManager::new(context.clone(), keyboard_info)?;
+    let manager = KeyboardManager::<ApexProTkl>::new(context.clone())?;
+    manager
+        .sender
+        .send(manager::Message::SetLighting(lighting::lighting_effect_from_env()))?;
     manager.sender.send(manager::Message::RefreshScreen)?;
     let manager_handle = manager.spawn()?;
 
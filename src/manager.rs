@@ -1,8 +1,11 @@
-use crate::keyboard::{KeyboardDevice, KeyboardInfo};
-use anyhow::{anyhow, bail, Result};
+use crate::input::{InputWatcher, TypingStats};
+use crate::keyboard::{KeyboardDevice, KeyboardType};
+use crate::lighting::{EffectRunner, LightingEffect, KEY_COUNT};
+use crate::remap::{remap_config_path, RemapWatcher};
+use anyhow::{Context, Result};
 use embedded_graphics::{
     drawable::Drawable,
-    fonts::{Font12x16, Text},
+    fonts::{Font12x16, Font6x8, Text},
     pixelcolor::BinaryColor,
     prelude::{Font, Point, Primitive},
     primitives::{Circle, Line},
@@ -10,38 +13,61 @@ use embedded_graphics::{
 };
 use rusb::{Hotplug, Registration, UsbContext};
 use std::{
+    cell::RefCell,
     fmt,
-    sync::mpsc::{channel, Receiver, Sender},
+    marker::PhantomData,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-pub struct KeyboardManager {
-    keyboard_info: KeyboardInfo,
+/// How often the manager wakes up on its own to advance lighting effects
+/// like breathing or reactive decay.
+const EFFECT_TICK_INTERVAL: Duration = Duration::from_millis(33);
+
+/// How many times in total to try opening the keyboard after it arrives
+/// before giving up on that hotplug event.
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+
+pub struct KeyboardManager<K: KeyboardType> {
     receiver: Receiver<Message>,
     pub sender: Sender<Message>,
     context: rusb::Context,
     callback_handle: Registration<rusb::Context>,
+    device: RefCell<Option<KeyboardDevice<K, rusb::Context>>>,
+    lighting: RefCell<EffectRunner>,
+    typing_stats: RefCell<TypingStats>,
+    input_watcher: RefCell<Option<InputWatcher>>,
+    remap_watcher: RefCell<Option<RemapWatcher>>,
+    active_layer: RefCell<(usize, String)>,
+    _keyboard_type: PhantomData<K>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Message {
     DeviceArrived,
     DeviceLeft,
     RefreshScreen,
+    SetLighting(LightingEffect),
+    KeyEvent { code: u16, pressed: bool, at: Instant },
+    LayerChanged { index: usize, name: String },
 }
 
-impl KeyboardManager {
+impl<K: KeyboardType> KeyboardManager<K> {
     pub fn spawn(self) -> Result<JoinHandle<()>> {
         let handle = thread::Builder::new()
             .name(format!(
-                "KeyboardManager-{}:{}",
-                self.keyboard_info.vendor_id, self.keyboard_info.product_id
+                "KeyboardManager-{:04x}:{:04x}",
+                K::VENDOR_ID,
+                K::PRODUCT_ID
             ))
             .spawn(move || loop {
-                match self.receiver.recv() {
+                match self.receiver.recv_timeout(EFFECT_TICK_INTERVAL) {
                     Ok(msg) => self.handle_message(msg),
-                    Err(error) => {
-                        tracing::error!(%error);
+                    Err(RecvTimeoutError::Timeout) => self.tick_lighting(),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        tracing::error!("manager channel disconnected");
                         break;
                     }
                 }
@@ -50,71 +76,250 @@ impl KeyboardManager {
     }
 }
 
-impl KeyboardManager {
-    pub fn new(context: rusb::Context, keyboard_info: KeyboardInfo) -> Result<Self> {
+impl<K: KeyboardType> KeyboardManager<K> {
+    pub fn new(context: rusb::Context) -> Result<Self> {
         let (sender, receiver) = channel();
         let callback_handle = context.register_callback(
-            Some(keyboard_info.vendor_id),
-            Some(keyboard_info.product_id),
+            Some(K::VENDOR_ID),
+            Some(K::PRODUCT_ID),
             None,
             Box::new(KeyboardWatcher {
                 sender: sender.clone(),
             }),
         )?;
         Ok(Self {
-            keyboard_info,
             receiver,
             sender,
             context,
             callback_handle,
+            device: RefCell::new(None),
+            lighting: RefCell::new(EffectRunner::default()),
+            typing_stats: RefCell::new(TypingStats::default()),
+            input_watcher: RefCell::new(None),
+            remap_watcher: RefCell::new(None),
+            active_layer: RefCell::new((0, "BASE".to_string())),
+            _keyboard_type: PhantomData,
         })
     }
 
     #[tracing::instrument(skip(self))]
     fn handle_message(&self, message: Message) {
         tracing::info!("manager message received");
-        let res = match message {
-            Message::DeviceArrived => self.draw_screen(),
-            Message::DeviceLeft => Ok(()),
+        let res = match &message {
+            Message::DeviceArrived => self.device_arrived(),
+            Message::DeviceLeft => {
+                self.device.borrow_mut().take();
+                self.input_watcher.borrow_mut().take();
+                self.remap_watcher.borrow_mut().take();
+                Ok(())
+            }
             Message::RefreshScreen => self.draw_screen(),
+            Message::SetLighting(effect) => self.set_lighting(effect.clone()),
+            Message::KeyEvent { code, pressed, at } => self.key_event(*code, *pressed, *at),
+            Message::LayerChanged { index, name } => {
+                *self.active_layer.borrow_mut() = (*index, name.clone());
+                Ok(())
+            }
         };
         if let Err(error) = res {
             tracing::error!(?error, ?message, "error handling message");
         }
     }
 
-    fn draw_screen(&self) -> Result<()> {
-        let mut keyboard = KeyboardDevice::new(&self.context, self.keyboard_info)?;
-
-        let hostname = hostname::get()?;
-        let hostname = hostname
-            .to_str()
-            .ok_or_else(|| anyhow!("Invalid hostname {:?}", hostname::get()))?
-            .to_uppercase();
-
-        Text::new(&hostname, Point::new(0, 0))
-            .into_styled(TextStyle::new(Font12x16, BinaryColor::On))
-            .draw(&mut keyboard)?;
-
-        Line::new(
-            Point::new(0, Font12x16::CHARACTER_SIZE.height as i32),
-            Point::new(
-                Font12x16::str_width(&hostname) as i32,
-                Font12x16::CHARACTER_SIZE.height as i32,
-            ),
-        )
-        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
-        .draw(&mut keyboard)?;
-
-        keyboard.flush_screen()?;
+    /// Reopen the persistent [`KeyboardDevice`] with a bounded retry/backoff,
+    /// then restart the input watcher and redraw.
+    fn device_arrived(&self) -> Result<()> {
+        let mut delay = RECONNECT_BACKOFF;
+        for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+            match KeyboardDevice::new(&self.context) {
+                Ok(device) => {
+                    *self.device.borrow_mut() = Some(device);
+                    break;
+                }
+                Err(error) if attempt < MAX_RECONNECT_ATTEMPTS => {
+                    tracing::warn!(?error, attempt, "keyboard not ready yet, retrying");
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                Err(error) => return Err(error).context("reconnecting to keyboard"),
+            }
+        }
+
+        // RemapWatcher grabs the evdev node exclusively, so only start
+        // InputWatcher when remapping isn't active.
+        if !self.start_remap_watcher() {
+            let watcher = InputWatcher::spawn(K::VENDOR_ID, K::PRODUCT_ID, self.sender.clone());
+            match watcher {
+                Ok(watcher) => *self.input_watcher.borrow_mut() = Some(watcher),
+                Err(error) => tracing::error!(?error, "failed to start input watcher"),
+            }
+        }
+
+        self.draw_screen()
+    }
+
+    /// Key remapping is optional: a no-op if there's no layout config on
+    /// disk. Returns whether a remap watcher was started.
+    fn start_remap_watcher(&self) -> bool {
+        let config_path = remap_config_path();
+        if !config_path.exists() {
+            tracing::info!(?config_path, "no remap config found, skipping key remapping");
+            return false;
+        }
+
+        let watcher = RemapWatcher::spawn(
+            K::VENDOR_ID,
+            K::PRODUCT_ID,
+            config_path,
+            self.sender.clone(),
+        );
+        match watcher {
+            Ok(watcher) => {
+                *self.remap_watcher.borrow_mut() = Some(watcher);
+                true
+            }
+            Err(error) => {
+                tracing::error!(?error, "failed to start key remapping");
+                false
+            }
+        }
+    }
+
+    fn key_event(&self, code: u16, pressed: bool, at: Instant) -> Result<()> {
+        if pressed {
+            self.typing_stats.borrow_mut().record_keydown(code, at);
+            if let Some(key_index) = crate::lighting::key_index_for_code(code) {
+                self.lighting.borrow_mut().register_keypress(key_index);
+            }
+            self.draw_screen()?;
+        }
         Ok(())
     }
+
+    fn set_lighting(&self, effect: LightingEffect) -> Result<()> {
+        self.lighting.borrow_mut().set_effect(effect);
+        self.render_lighting()
+    }
+
+    /// Advance breathing/reactive effects on the periodic tick. A no-op
+    /// while there's no cached device; reconnection is `device_arrived`'s job.
+    fn tick_lighting(&self) {
+        if self.device.borrow().is_none() {
+            return;
+        }
+        self.lighting.borrow_mut().advance();
+        if let Err(error) = self.render_lighting() {
+            tracing::error!(?error, "error advancing lighting effect");
+        }
+    }
+
+    fn render_lighting(&self) -> Result<()> {
+        let frame = self.lighting.borrow().render(KEY_COUNT, 255);
+        self.with_device(|keyboard| keyboard.set_color_frame(&frame))
+    }
+
+    fn draw_screen(&self) -> Result<()> {
+        let stats_snapshot = self.typing_stats.borrow();
+        let wpm_line = format!(
+            "{:.0} WPM  {} KEYS",
+            stats_snapshot.wpm(),
+            stats_snapshot.keystroke_count()
+        );
+        let recent_codes: Vec<String> = stats_snapshot
+            .recent_codes()
+            .map(u16::to_string)
+            .collect();
+        drop(stats_snapshot);
+        let recent_line = format!("LAST: {}", recent_codes.join(" "));
+        let (layer_index, layer_name) = self.active_layer.borrow().clone();
+        let layer_line = format!("LAYER {}: {}", layer_index, layer_name);
+
+        self.with_device(|keyboard| {
+            let hostname = hostname::get()?;
+            let hostname = hostname
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid hostname {:?}", hostname::get()))?
+                .to_uppercase();
+
+            Text::new(&hostname, Point::new(0, 0))
+                .into_styled(TextStyle::new(Font12x16, BinaryColor::On))
+                .draw(keyboard)?;
+
+            Line::new(
+                Point::new(0, Font12x16::CHARACTER_SIZE.height as i32),
+                Point::new(
+                    Font12x16::str_width(&hostname) as i32,
+                    Font12x16::CHARACTER_SIZE.height as i32,
+                ),
+            )
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(keyboard)?;
+
+            // Three Font6x8 lines stacked below the Font12x16 hostname
+            // header; warn instead of silently clipping if they don't fit.
+            let stats_top = Font12x16::CHARACTER_SIZE.height as i32;
+            let line_height = Font6x8::CHARACTER_SIZE.height as i32;
+            let stats_bottom = stats_top + 3 * line_height;
+            if stats_bottom > K::OLED_SIZE.height as i32 {
+                tracing::warn!(
+                    stats_bottom,
+                    screen_height = K::OLED_SIZE.height,
+                    "typing stats widget does not fit on screen, bottom line(s) will be clipped"
+                );
+            }
+
+            Text::new(&wpm_line, Point::new(0, stats_top))
+                .into_styled(TextStyle::new(Font6x8, BinaryColor::On))
+                .draw(keyboard)?;
+            Text::new(&recent_line, Point::new(0, stats_top + line_height))
+                .into_styled(TextStyle::new(Font6x8, BinaryColor::On))
+                .draw(keyboard)?;
+            Text::new(&layer_line, Point::new(0, stats_top + 2 * line_height))
+                .into_styled(TextStyle::new(Font6x8, BinaryColor::On))
+                .draw(keyboard)?;
+
+            keyboard.flush_screen()
+        })
+    }
+
+    /// Run `f` against the persistent device, opening it first if needed.
+    /// Drops the cached device on a transient USB error instead of killing
+    /// the manager thread.
+    fn with_device<T>(
+        &self,
+        f: impl FnOnce(&mut KeyboardDevice<K, rusb::Context>) -> Result<T>,
+    ) -> Result<T> {
+        if self.device.borrow().is_none() {
+            let device = KeyboardDevice::new(&self.context).context("opening keyboard device")?;
+            *self.device.borrow_mut() = Some(device);
+        }
+
+        let mut slot = self.device.borrow_mut();
+        let device = slot.as_mut().expect("device was just ensured present");
+        let result = f(device);
+
+        if let Err(error) = &result {
+            if is_transient_usb_error(error) {
+                tracing::warn!(?error, "transient USB error, will reconnect next time");
+                *slot = None;
+            }
+        }
+
+        result
+    }
+}
+
+fn is_transient_usb_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<rusb::Error>(),
+        Some(rusb::Error::NoDevice) | Some(rusb::Error::Io) | Some(rusb::Error::Pipe)
+    )
 }
 
-impl fmt::Debug for KeyboardManager {
+impl<K: KeyboardType> fmt::Debug for KeyboardManager<K> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("KeyboardManager")
-            .field("keyboard_info", &self.keyboard_info)
+            .field("keyboard_type", &format_args!("{:04x}:{:04x}", K::VENDOR_ID, K::PRODUCT_ID))
             .field("receiver", &"..")
             .field("sender", &"..")
             .finish()
@@ -0,0 +1,380 @@
+//! An optional uinput remapping layer, inspired by layered keyboard
+//! firmware (e.g. keyberon): raw events off the keyboard's evdev node are
+//! translated through a layer/layout table and re-emitted through a
+//! virtual `uinput` device, so the rest of the system only ever sees the
+//! remapped keys.
+
+use anyhow::{Context, Result};
+use evdev::{
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+    AttributeSet, Device, EventType, InputEvent, InputEventKind, Key,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    input::{find_device_node, set_nonblocking},
+    manager::Message,
+};
+
+const DEFAULT_CONFIG_PATH: &str = "remap.toml";
+/// How long to sleep between non-blocking reads of the grabbed source
+/// device when nothing's queued. Same reasoning as [`crate::input`]'s
+/// `POLL_INTERVAL`.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A single action a remapped key can perform, modeled on keyberon's
+/// action type: a plain key, a layer switch, or a macro.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Emit a different raw keycode.
+    Key(u16),
+    /// Toggle a layer on or off on press.
+    Layer(usize),
+    /// Activate a layer while held, deactivate on release.
+    MomentaryLayer(usize),
+    /// Tap a sequence of keycodes in order when pressed.
+    Macro(Vec<u16>),
+}
+
+/// One layer of the layout table: a name for the on-screen indicator, plus
+/// per-key overrides keyed by physical (evdev) keycode. Keys with no entry
+/// fall through to the next layer down, and ultimately pass through
+/// unmodified.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct Layer {
+    pub name: String,
+    #[serde(default)]
+    pub actions: HashMap<u16, Action>,
+}
+
+/// The full layout table loaded from a config file at startup. `layers[0]`
+/// is the always-active base layer.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemapConfig {
+    pub layers: Vec<Layer>,
+}
+
+fn load_config(path: &Path) -> Result<RemapConfig> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading remap config {:?}", path))?;
+    toml::from_str(&text).with_context(|| format!("parsing remap config {:?}", path))
+}
+
+/// Where to look for the remap layout, overridable with `APEX_REMAP_CONFIG`.
+pub fn remap_config_path() -> PathBuf {
+    std::env::var_os("APEX_REMAP_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+}
+
+/// Tracks which layers are active and resolves incoming keycodes to
+/// actions against the loaded [`RemapConfig`].
+struct RemapEngine {
+    config: RemapConfig,
+    // Stack of active layer indices, base layer (0) always at the bottom.
+    active_layers: Vec<usize>,
+}
+
+impl RemapEngine {
+    fn new(config: RemapConfig) -> Self {
+        Self {
+            config,
+            active_layers: vec![0],
+        }
+    }
+
+    fn current_layer(&self) -> usize {
+        *self.active_layers.last().unwrap_or(&0)
+    }
+
+    fn current_layer_name(&self) -> &str {
+        self.config
+            .layers
+            .get(self.current_layer())
+            .map(|layer| layer.name.as_str())
+            .unwrap_or("BASE")
+    }
+
+    fn resolve_action(&self, code: u16) -> Action {
+        for &layer in self.active_layers.iter().rev() {
+            if let Some(action) = self
+                .config
+                .layers
+                .get(layer)
+                .and_then(|layer| layer.actions.get(&code))
+            {
+                return action.clone();
+            }
+        }
+        Action::Key(code)
+    }
+
+    fn toggle_layer(&mut self, layer: usize) {
+        if let Some(pos) = self.active_layers.iter().rposition(|&l| l == layer) {
+            self.active_layers.remove(pos);
+        } else {
+            self.active_layers.push(layer);
+        }
+    }
+
+    fn process_event(&mut self, target: &mut VirtualDevice, code: u16, pressed: bool) -> Result<()> {
+        match self.resolve_action(code) {
+            Action::Key(mapped) => emit_key(target, mapped, pressed)?,
+            Action::Layer(layer) => {
+                if pressed {
+                    self.toggle_layer(layer);
+                }
+            }
+            Action::MomentaryLayer(layer) => {
+                if pressed {
+                    self.active_layers.push(layer);
+                } else if let Some(pos) = self.active_layers.iter().rposition(|&l| l == layer) {
+                    self.active_layers.remove(pos);
+                }
+            }
+            Action::Macro(sequence) => {
+                if pressed {
+                    for code in sequence {
+                        emit_key(target, code, true)?;
+                        emit_key(target, code, false)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn emit_key(target: &mut VirtualDevice, code: u16, pressed: bool) -> Result<()> {
+    let event = InputEvent::new(EventType::KEY, code, pressed as i32);
+    target
+        .emit(&[event])
+        .context("emitting remapped key event")
+}
+
+fn build_virtual_device() -> Result<VirtualDevice> {
+    let mut keys = AttributeSet::<Key>::new();
+    for code in 0..256u16 {
+        keys.insert(Key::new(code));
+    }
+    VirtualDeviceBuilder::new()
+        .context("opening /dev/uinput")?
+        .name("steel-series-apex-remap")
+        .with_keys(&keys)
+        .context("declaring virtual key capabilities")?
+        .build()
+        .context("building uinput virtual device")
+}
+
+/// Reads raw key events off the keyboard's evdev node, grabs it for
+/// exclusive access, and re-emits remapped events through a `uinput`
+/// virtual device on its own thread. Mirrors
+/// [`InputWatcher`](crate::input::InputWatcher)'s lifecycle, and also
+/// reports raw key events as [`Message::KeyEvent`] so typing stats keep
+/// working while remapping is active.
+pub struct RemapWatcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RemapWatcher {
+    pub fn spawn(
+        vendor_id: u16,
+        product_id: u16,
+        config_path: PathBuf,
+        sender: Sender<Message>,
+    ) -> Result<Self> {
+        let path = find_device_node(vendor_id, product_id)?;
+        let config = load_config(&config_path)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+
+        let handle = thread::Builder::new()
+            .name(format!("RemapWatcher-{:x}:{:x}", vendor_id, product_id))
+            .spawn(move || {
+                if let Err(error) = remap_loop(&path, config, &sender, &thread_stop) {
+                    tracing::error!(?error, "remap watcher stopped");
+                }
+            })
+            .context("spawning remap watcher thread")?;
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            if let Err(error) = handle.join() {
+                tracing::error!(?error, "remap watcher thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for RemapWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn remap_loop(
+    path: &Path,
+    config: RemapConfig,
+    sender: &Sender<Message>,
+    stop: &Arc<AtomicBool>,
+) -> Result<()> {
+    let mut source = Device::open(path).context("opening input device for remapping")?;
+    source
+        .grab()
+        .context("grabbing input device for exclusive remap access")?;
+    set_nonblocking(&source)?;
+    let mut target = build_virtual_device()?;
+    let mut engine = RemapEngine::new(config);
+
+    while !stop.load(Ordering::SeqCst) {
+        let events = match source.fetch_events() {
+            Ok(events) => events,
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(error) => return Err(error).context("reading input events"),
+        };
+        for event in events {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let key = match event.kind() {
+                InputEventKind::Key(key) => key,
+                _ => continue,
+            };
+            // Linux autorepeat (`value() == 2`) isn't a release; treating it
+            // as one would pop `MomentaryLayer`s as soon as repeat kicks in.
+            let repeat = event.value() == 2;
+            let pressed = event.value() == 1;
+
+            // `source` is grabbed exclusively, so `InputWatcher` can't read
+            // it too: report raw key events here instead. Autorepeat is
+            // forwarded as a `pressed: false` no-op, same as `key_event`
+            // already treats it.
+            if sender
+                .send(Message::KeyEvent {
+                    code: key.code(),
+                    pressed: pressed && !repeat,
+                    at: Instant::now(),
+                })
+                .is_err()
+            {
+                return Ok(());
+            }
+
+            if repeat {
+                continue;
+            }
+
+            let layer_before = engine.current_layer();
+            engine.process_event(&mut target, key.code(), pressed)?;
+
+            if engine.current_layer() != layer_before {
+                let changed = sender.send(Message::LayerChanged {
+                    index: engine.current_layer(),
+                    name: engine.current_layer_name().to_string(),
+                });
+                if changed.is_err() || sender.send(Message::RefreshScreen).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toy_config() -> RemapConfig {
+        RemapConfig {
+            layers: vec![
+                Layer {
+                    name: "BASE".to_string(),
+                    actions: HashMap::from([(30, Action::MomentaryLayer(1))]),
+                },
+                Layer {
+                    name: "NAV".to_string(),
+                    actions: HashMap::from([(31, Action::Key(103))]),
+                },
+                Layer {
+                    name: "FN".to_string(),
+                    actions: HashMap::new(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn resolve_action_falls_through_to_base_layer() {
+        let mut engine = RemapEngine::new(toy_config());
+        engine.toggle_layer(1);
+        // Code 30 is only bound on the base layer, which is always active.
+        assert!(matches!(
+            engine.resolve_action(30),
+            Action::MomentaryLayer(1)
+        ));
+    }
+
+    #[test]
+    fn resolve_action_passes_through_unbound_keys() {
+        let engine = RemapEngine::new(toy_config());
+        assert!(matches!(engine.resolve_action(99), Action::Key(99)));
+    }
+
+    #[test]
+    fn resolve_action_prefers_the_topmost_active_layer() {
+        let mut engine = RemapEngine::new(toy_config());
+        engine.config.layers[0].actions.insert(31, Action::Key(32));
+        engine.toggle_layer(1);
+        // Only resolves if the active-layer stack is searched top-down.
+        assert!(matches!(engine.resolve_action(31), Action::Key(103)));
+    }
+
+    #[test]
+    fn toggle_layer_activates_then_deactivates() {
+        let mut engine = RemapEngine::new(toy_config());
+        assert_eq!(engine.current_layer(), 0);
+        engine.toggle_layer(1);
+        assert_eq!(engine.current_layer(), 1);
+        assert_eq!(engine.current_layer_name(), "NAV");
+        engine.toggle_layer(1);
+        assert_eq!(engine.current_layer(), 0);
+        assert_eq!(engine.current_layer_name(), "BASE");
+    }
+
+    #[test]
+    fn momentary_layer_activates_on_press_and_deactivates_on_release() {
+        let mut engine = RemapEngine::new(toy_config());
+        // `process_event` needs a real uinput `VirtualDevice`; drive
+        // `active_layers` directly the same way it does for this action.
+        engine.active_layers.push(1);
+        assert_eq!(engine.current_layer(), 1);
+        if let Some(pos) = engine.active_layers.iter().rposition(|&l| l == 1) {
+            engine.active_layers.remove(pos);
+        }
+        assert_eq!(engine.current_layer(), 0);
+    }
+}